@@ -1,8 +1,13 @@
 use async_trait::async_trait;
-use serde::{Deserialize, Serialize};
-use server::{Server, ServerErrorKind};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufStream};
-use tokio::net::{TcpListener, TcpStream};
+use server::{serve_protocol, FlowControl, JsonMethodProtocol, Server, ServerErrorKind};
+use tokio::net::TcpListener;
+use traits::SolutionError;
+
+/// `isPrime` requests above this magnitude run Miller-Rabin with longer
+/// exponents (up to 64 bits rather than 32), so each witness takes more
+/// squarings than a small-number check, and they're weighed more in the
+/// connection's flow-control credit bucket.
+const LARGE_NUMBER_THRESHOLD: u64 = u32::MAX as u64;
 
 /// Prime Time
 ///
@@ -42,7 +47,23 @@ use tokio::net::{TcpListener, TcpStream};
 ///
 /// Make sure you can handle at least 5 simultaneous clients.
 #[derive(Debug, Default)]
-pub struct PrimeTimeServer;
+pub struct PrimeTimeServer {
+    flow_control: FlowControl,
+}
+
+impl PrimeTimeServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a server whose per-connection credit bucket is configured
+    /// by `flow_control`, instead of the default rate. A clean place to
+    /// tune down `isPrime`'s expensive large-number checks without
+    /// editing the handler itself.
+    pub fn with_flow_control(flow_control: FlowControl) -> Self {
+        Self { flow_control }
+    }
+}
 
 #[async_trait]
 impl Server for PrimeTimeServer {
@@ -62,517 +83,345 @@ impl Server for PrimeTimeServer {
 
             println!("Connection open\n");
 
+            let flow_control = self.flow_control;
+
             // A new task is spawned for each inbound socket. The socket is
             // moved to the new task and processed there.
-            tokio::spawn(async move { process(socket).await });
+            tokio::spawn(async move {
+                let mut protocol = JsonMethodProtocol::with_flow_control(flow_control);
+                protocol.register("isPrime", is_prime_handler);
+                protocol.register_cost("isPrime", cost_of_is_prime);
+
+                serve_protocol(socket, protocol).await
+            });
         }
     }
 }
 
-/// Processes a connection
+/// The `isPrime` method handler, registered on a `JsonMethodProtocol`.
 ///
-/// Returns a `Result` which is empty on the success path and
-/// contains a `ServerErrorKind` on the error path
-async fn process(stream: TcpStream) -> Result<(), ServerErrorKind> {
-    let mut stream = BufStream::new(stream);
-    let mut line = vec![];
-    let mut should_continue = true;
-
-    while should_continue {
-        let read_len = stream
-            .read_until(b'\n', &mut line)
-            .await
-            .map_err(|_| ServerErrorKind::ReadFail)?;
-
-        if read_len > 0 {
-            // Construct a request from the u8 vec
-            let req = Request::from_bytes(line.as_slice());
-
-            // Consume the request and construct a response
-            let resp = req.process();
-
-            // return an error if the response is malformed
-            // otherwise return the response
-            let response = match resp {
-                Response::ConformingResp { .. } => resp.into_bytes(),
-                Response::MalformedResp => {
-                    should_continue = false;
-                    resp.into_bytes()
-                }
-            };
-
-            // If there's something to send
-            if !response.is_empty() {
-                // Send back the result
-                stream
-                    .write_all(&response)
-                    .await
-                    .map_err(|_| ServerErrorKind::WriteFail)?;
-
-                // Flush the buffer to ensure it is sent
-                stream
-                    .flush()
-                    .await
-                    .map_err(|_| ServerErrorKind::WriteFail)?;
-            }
-        } else {
-            should_continue = false;
-        }
+/// `args` must contain a `number` field holding a JSON number; anything
+/// else (missing field, or a non-number value) is malformed.
+async fn is_prime_handler(args: serde_json::Value) -> Result<serde_json::Value, SolutionError> {
+    let number = match args.get("number") {
+        Some(serde_json::Value::Number(number)) => number,
+        _ => return Err(SolutionError::Request(b"malformed\n".to_vec())),
+    };
+
+    let prime = match classify_number(number) {
+        ParsedNumber::Whole(n) => is_prime_u64(n),
+        ParsedNumber::NotPrimeCandidate => false,
+    };
+
+    Ok(serde_json::json!({ "prime": prime }))
+}
 
-        line.clear();
+/// The flow-control cost of an `isPrime` request, registered on the
+/// connection's `JsonMethodProtocol` alongside the handler itself.
+///
+/// A number beyond `LARGE_NUMBER_THRESHOLD` costs more credits, since
+/// every witness of the Miller-Rabin loop then exponentiates by an
+/// exponent of up to 64 bits instead of at most 32, so it runs more
+/// `mulmod` steps than a small number does. Anything that isn't a whole `u64`
+/// number (missing, fractional, negative, or too large to be prime)
+/// costs the default: there's no heavier test to run for it.
+fn cost_of_is_prime(args: &serde_json::Value) -> u32 {
+    match args.get("number").and_then(serde_json::Value::as_number) {
+        Some(number) => match classify_number(number) {
+            ParsedNumber::Whole(n) if n > LARGE_NUMBER_THRESHOLD => 5,
+            _ => 1,
+        },
+        None => 1,
     }
-
-    Ok(())
 }
 
-/// Conforming Request object
-/// Used for deserializing JSON bytes received
-#[derive(Debug, Deserialize, PartialEq, Serialize)]
-struct ConformingReqObj {
-    pub method: String,
-    pub number: f64,
+/// The result of classifying a request's `number` field.
+///
+/// Whole numbers that fit in a `u64` go on to the Miller-Rabin test;
+/// everything else (a fractional value, a negative number, or an integer
+/// too large for `u64`) can never be prime as far as this server is
+/// concerned.
+#[derive(Debug, PartialEq)]
+enum ParsedNumber {
+    Whole(u64),
+    NotPrimeCandidate,
 }
 
-/// Conforming Response object
-/// Used for serializing JSON before sending
-#[derive(Debug, Deserialize, PartialEq, Serialize)]
-struct ConformingRespObj {
-    method: String,
-    prime: bool,
-}
+/// The largest integer an `f64` can hold without losing precision (2^53).
+const MAX_SAFE_FLOAT_INT: f64 = 9_007_199_254_740_992.0;
 
-/// Request type
-/// Constructed based on bytes and verified if it
-/// satisfies the solution conditions
-#[derive(Debug, PartialEq)]
-enum Request {
-    ConformingReq { method: String, number: f64 },
-    MalformedReq,
-}
+/// Classifies a JSON `number` field.
+///
+/// An integer literal that fits in a `u64` is stored by `serde_json` as
+/// an exact integer (no `f64` round-trip involved), so whole numbers
+/// above 2^53 keep their precision as long as they're written without a
+/// decimal point or exponent. A `number` written as a float (e.g.
+/// `2.0`) is only accepted if its whole value is within `f64`'s exact
+/// integer range; anything else, or anything with a genuine fractional
+/// part, is never prime-answerable.
+fn classify_number(number: &serde_json::Number) -> ParsedNumber {
+    if let Some(n) = number.as_u64() {
+        return ParsedNumber::Whole(n);
+    }
 
-/// Response type
-/// Constructed based on a processed request
-#[derive(Debug, PartialEq)]
-enum Response {
-    ConformingResp { method: String, prime: bool },
-    MalformedResp,
-}
+    if number.as_i64().is_some() {
+        return ParsedNumber::NotPrimeCandidate;
+    }
 
-impl Request {
-    /// Creates a `Request` from provided bytes
-    /// and verifies if it satisfies the solution conditions
-    ///
-    /// If it does, it returns a `Request::ConformingReq`
-    /// Otherwise, it returns a `Request::MalformedReq`
-    fn from_bytes(line: &[u8]) -> Request {
-        let obj = serde_json::from_slice::<ConformingReqObj>(line).ok();
-
-        match obj {
-            Some(ConformingReqObj { method, number }) => {
-                if method == "isPrime" {
-                    Request::ConformingReq { method, number }
-                } else {
-                    Request::MalformedReq
-                }
-            }
-            None => Request::MalformedReq,
+    match number.as_f64() {
+        Some(value) if value.fract() == 0.0 && (0.0..=MAX_SAFE_FLOAT_INT).contains(&value) => {
+            ParsedNumber::Whole(value as u64)
         }
+        _ => ParsedNumber::NotPrimeCandidate,
     }
+}
 
-    /// Processes the request and returns a `Response`
-    fn process(self) -> Response {
-        match self {
-            Request::ConformingReq { method, number } => Response::ConformingResp {
-                method,
-                prime: is_prime(number),
-            },
-            Request::MalformedReq => Response::MalformedResp,
+/// Deterministic Miller-Rabin primality test for `u64`.
+///
+/// Witnesses `{2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37}` are exact for
+/// every `n < 3.3 * 10^24`, which covers the whole `u64` range.
+fn is_prime_u64(n: u64) -> bool {
+    if n < 2 {
+        return false;
+    }
+
+    const SMALL_PRIMES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+
+    for &p in SMALL_PRIMES.iter() {
+        if n == p {
+            return true;
+        }
+        if n.is_multiple_of(p) {
+            return false;
         }
     }
-}
 
-impl Response {
-    /// Converts the response into bytes ready to be sent
-    fn into_bytes(self) -> Vec<u8> {
-        match self {
-            Response::ConformingResp { method, prime } => {
-                let obj = ConformingRespObj { method, prime };
-                let mut res = serde_json::to_string(&obj).unwrap();
-
-                // Add newline
-                res.push('\n');
-                res.as_bytes().to_vec()
+    // Write n - 1 = 2^r * d with d odd
+    let mut d = n - 1;
+    let mut r = 0u32;
+    while d.is_multiple_of(2) {
+        d /= 2;
+        r += 1;
+    }
+
+    'witness: for &a in SMALL_PRIMES.iter() {
+        if a >= n {
+            continue;
+        }
+
+        let mut x = mulmod_pow(a, d, n);
+
+        if x == 1 || x == n - 1 {
+            continue;
+        }
+
+        for _ in 0..r - 1 {
+            x = mulmod(x, x, n);
+            if x == n - 1 {
+                continue 'witness;
             }
-            Response::MalformedResp => b"malformed\n".to_vec(),
         }
+
+        return false;
     }
+
+    true
 }
 
-/// Checks if a number is prime
-fn is_prime(number: f64) -> bool {
-    let n = number;
-    let number = number as i64;
+/// Computes `(a * b) mod m` without overflowing `u64`, by widening the
+/// intermediate product to `u128`.
+fn mulmod(a: u64, b: u64, m: u64) -> u64 {
+    ((a as u128 * b as u128) % m as u128) as u64
+}
 
-    if n.fract() != 0.0 || number < 2 {
-        false
-    } else {
-        let end = f64::sqrt(number as f64) as i64;
+/// Computes `(base^exp) mod m` via fast modular exponentiation, using
+/// `mulmod` for each multiplication to avoid overflow.
+fn mulmod_pow(base: u64, exp: u64, m: u64) -> u64 {
+    let mut result = 1u64;
+    let mut base = base % m;
+    let mut exp = exp;
 
-        !(2..=end).any(|n| number % n == 0)
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = mulmod(result, base, m);
+        }
+        base = mulmod(base, base, m);
+        exp >>= 1;
     }
+
+    result
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use traits::Protocol;
 
-    #[test]
-    fn test_deserialize_req() {
-        let line = b"{\"method\":\"isPrime\",\"number\":123}";
-
-        let obj: ConformingReqObj = serde_json::from_slice(line).unwrap();
-        assert_eq!(obj.method, "isPrime");
-        assert_eq!(obj.number, 123f64);
+    fn number(raw: &str) -> serde_json::Number {
+        serde_json::from_str(raw).unwrap()
     }
 
     #[test]
-    fn test_deserialize_inverted_req() {
-        let line = b"{\"number\":2,\"method\":\"isPrime\"}";
-
-        let obj: ConformingReqObj = serde_json::from_slice(line).unwrap();
-        assert_eq!(obj.method, "isPrime");
-        assert_eq!(obj.number, 2f64);
+    fn test_classify_number_whole() {
+        assert_eq!(classify_number(&number("123")), ParsedNumber::Whole(123));
+        assert_eq!(classify_number(&number("0")), ParsedNumber::Whole(0));
     }
 
     #[test]
-    fn test_deserialize_inverted_newline_req() {
-        let line = b"{\"number\":2,\"method\":\"isPrime\"}\n";
-
-        let obj: ConformingReqObj = serde_json::from_slice(line).unwrap();
-        assert_eq!(obj.method, "isPrime");
-        assert_eq!(obj.number, 2f64);
+    fn test_classify_number_whole_float() {
+        assert_eq!(classify_number(&number("2.0")), ParsedNumber::Whole(2));
     }
 
     #[test]
-    fn test_serialize_deserialize_resp() {
-        let obj_0 = ConformingRespObj {
-            method: "isPrime".to_string(),
-            prime: true,
-        };
-
-        let ser = serde_json::to_string(&obj_0).unwrap().as_bytes().to_vec();
-        let obj_1: ConformingRespObj = serde_json::from_slice(&ser).unwrap();
-
-        assert_eq!(obj_0, obj_1);
+    fn test_classify_number_fractional() {
+        assert_eq!(
+            classify_number(&number("4224223.1234")),
+            ParsedNumber::NotPrimeCandidate
+        );
     }
 
     #[test]
-    fn test_req_valid_success() {
-        let line = b"{\"method\":\"isPrime\",\"number\":123}";
-        let req = Request::from_bytes(line);
-
+    fn test_classify_number_negative() {
         assert_eq!(
-            req,
-            Request::ConformingReq {
-                method: "isPrime".to_string(),
-                number: 123f64
-            }
+            classify_number(&number("-3")),
+            ParsedNumber::NotPrimeCandidate
         );
     }
 
     #[test]
-    fn test_req_valid_inverted_success() {
-        let line = b"{\"number\":2,\"method\":\"isPrime\"}";
-        let req = Request::from_bytes(line);
-
+    fn test_classify_number_too_large_for_u64() {
+        // u64::MAX + 1
         assert_eq!(
-            req,
-            Request::ConformingReq {
-                method: "isPrime".to_string(),
-                number: 2f64
-            }
+            classify_number(&number("18446744073709551616")),
+            ParsedNumber::NotPrimeCandidate
         );
     }
 
     #[test]
-    fn test_req_malformed_json_error() {
-        let line = b"\"method\":\"isPrim\",\"number\":123}";
-        let req = Request::from_bytes(line);
-
-        assert_eq!(req, Request::MalformedReq);
+    fn test_classify_number_beyond_f64_precision_stays_exact() {
+        // 2^53 + 5, a value an f64 round-trip would corrupt
+        assert_eq!(
+            classify_number(&number("9007199254740997")),
+            ParsedNumber::Whole(9007199254740997)
+        );
     }
 
     #[test]
-    fn test_req_malformed_invalid_method_error() {
-        let line = b"{\"method\":\"isPrim\",\"number\":123}";
-        let req = Request::from_bytes(line);
-
-        assert_eq!(req, Request::MalformedReq);
+    fn test_is_prime_large_prime_near_u64_max() {
+        // 2^64 - 59 is prime
+        assert!(is_prime_u64(u64::MAX - 58));
     }
 
     #[test]
-    fn test_req_malformed_number_error() {
-        let line = b"{\"method\":\"isPrime\",\"number\":\"123\"}";
-        let req = Request::from_bytes(line);
-
-        assert_eq!(req, Request::MalformedReq);
+    fn test_is_prime_large_composite_near_u64_max() {
+        assert!(!is_prime_u64(u64::MAX));
     }
 
     #[test]
-    fn test_req_process_prime_success() {
-        let line = b"{\"method\":\"isPrime\",\"number\":11}";
-        let req = Request::from_bytes(line);
-
-        assert_eq!(
-            req,
-            Request::ConformingReq {
-                method: "isPrime".to_string(),
-                number: 11f64
-            }
-        );
-        assert_eq!(
-            req.process(),
-            Response::ConformingResp {
-                method: "isPrime".to_string(),
-                prime: true
-            }
-        );
+    fn test_is_prime_u64_small_values() {
+        assert!(!is_prime_u64(0));
+        assert!(!is_prime_u64(1));
+        assert!(is_prime_u64(2));
+        assert!(is_prime_u64(3));
+        assert!(!is_prime_u64(4));
     }
 
-    #[test]
-    fn test_req_process_prime_inverted_success() {
-        let line = b"{\"number\":2,\"method\":\"isPrime\"}";
-        let req = Request::from_bytes(line);
+    #[tokio::test]
+    async fn test_is_prime_handler_prime_success() {
+        let resp = is_prime_handler(serde_json::json!({ "number": 11 }))
+            .await
+            .unwrap();
 
-        assert_eq!(
-            req,
-            Request::ConformingReq {
-                method: "isPrime".to_string(),
-                number: 2f64
-            }
-        );
-        assert_eq!(
-            req.process(),
-            Response::ConformingResp {
-                method: "isPrime".to_string(),
-                prime: true
-            }
-        );
+        assert_eq!(resp, serde_json::json!({ "prime": true }));
     }
 
-    #[test]
-    fn test_req_process_not_prime_success() {
-        let line = b"{\"method\":\"isPrime\",\"number\":9}";
-        let req = Request::from_bytes(line);
+    #[tokio::test]
+    async fn test_is_prime_handler_not_prime_success() {
+        let resp = is_prime_handler(serde_json::json!({ "number": 9 }))
+            .await
+            .unwrap();
 
-        assert_eq!(
-            req,
-            Request::ConformingReq {
-                method: "isPrime".to_string(),
-                number: 9f64
-            }
-        );
-        assert_eq!(
-            req.process(),
-            Response::ConformingResp {
-                method: "isPrime".to_string(),
-                prime: false
-            }
-        );
+        assert_eq!(resp, serde_json::json!({ "prime": false }));
     }
 
-    #[test]
-    fn test_req_process_prime_2_success() {
-        let line = b"{\"method\":\"isPrime\",\"number\":2}";
-        let req = Request::from_bytes(line);
+    #[tokio::test]
+    async fn test_is_prime_handler_negative_not_prime() {
+        let resp = is_prime_handler(serde_json::json!({ "number": -3 }))
+            .await
+            .unwrap();
 
-        assert_eq!(
-            req,
-            Request::ConformingReq {
-                method: "isPrime".to_string(),
-                number: 2f64
-            }
-        );
-        assert_eq!(
-            req.process(),
-            Response::ConformingResp {
-                method: "isPrime".to_string(),
-                prime: true
-            }
-        );
+        assert_eq!(resp, serde_json::json!({ "prime": false }));
     }
 
-    #[test]
-    fn test_req_to_vec_prime_success() {
-        let line = b"{\"method\":\"isPrime\",\"number\":778013}\n";
-        let req = Request::from_bytes(line);
+    #[tokio::test]
+    async fn test_is_prime_handler_large_prime_beyond_f64_precision() {
+        let resp = is_prime_handler(serde_json::json!({ "number": 9007199254740997u64 }))
+            .await
+            .unwrap();
 
-        assert_eq!(
-            req,
-            Request::ConformingReq {
-                method: "isPrime".to_string(),
-                number: 778013f64
-            }
-        );
-        let resp = req.process();
-        assert_eq!(
-            resp,
-            Response::ConformingResp {
-                method: "isPrime".to_string(),
-                prime: true
-            }
-        );
-        assert_eq!(
-            resp.into_bytes(),
-            "{\"method\":\"isPrime\",\"prime\":true}\n"
-                .to_string()
-                .as_bytes()
-                .to_vec()
-        );
+        assert_eq!(resp, serde_json::json!({ "prime": true }));
     }
 
-    #[test]
-    fn test_req_to_vec_prime_inverted_success() {
-        let line = b"{\"number\":2,\"method\":\"isPrime\"}";
-        let req = Request::from_bytes(line);
+    #[tokio::test]
+    async fn test_is_prime_handler_missing_number_is_malformed() {
+        let err = is_prime_handler(serde_json::json!({})).await.unwrap_err();
 
-        assert_eq!(
-            req,
-            Request::ConformingReq {
-                method: "isPrime".to_string(),
-                number: 2f64
-            }
-        );
-        let resp = req.process();
-        assert_eq!(
-            resp,
-            Response::ConformingResp {
-                method: "isPrime".to_string(),
-                prime: true
-            }
-        );
-        assert_eq!(
-            resp.into_bytes(),
-            "{\"method\":\"isPrime\",\"prime\":true}\n"
-                .to_string()
-                .as_bytes()
-                .to_vec()
-        );
+        assert_eq!(err, SolutionError::Request(b"malformed\n".to_vec()));
     }
 
-    #[test]
-    fn test_req_to_vec_prime_0_success() {
-        let line = b"{\"number\":0,\"method\":\"isPrime\"}";
-        let req = Request::from_bytes(line);
+    #[tokio::test]
+    async fn test_is_prime_handler_non_number_is_malformed() {
+        let err = is_prime_handler(serde_json::json!({ "number": "123" }))
+            .await
+            .unwrap_err();
 
-        assert_eq!(
-            req,
-            Request::ConformingReq {
-                method: "isPrime".to_string(),
-                number: 0f64
-            }
-        );
-        let resp = req.process();
-        assert_eq!(
-            resp,
-            Response::ConformingResp {
-                method: "isPrime".to_string(),
-                prime: false
-            }
-        );
-        assert_eq!(
-            resp.into_bytes(),
-            "{\"method\":\"isPrime\",\"prime\":false}\n"
-                .to_string()
-                .as_bytes()
-                .to_vec()
-        );
+        assert_eq!(err, SolutionError::Request(b"malformed\n".to_vec()));
     }
 
-    #[test]
-    fn test_req_to_vec_prime_1_success() {
-        let line = b"{\"number\":1,\"method\":\"isPrime\"}";
-        let req = Request::from_bytes(line);
+    #[tokio::test]
+    async fn test_registered_is_prime_end_to_end() {
+        let mut protocol = JsonMethodProtocol::new();
+        protocol.register("isPrime", is_prime_handler);
+
+        let response = protocol
+            .process_request(b"{\"method\":\"isPrime\",\"number\":778013}\n")
+            .await
+            .unwrap();
 
         assert_eq!(
-            req,
-            Request::ConformingReq {
-                method: "isPrime".to_string(),
-                number: 1f64
-            }
-        );
-        let resp = req.process();
-        assert_eq!(
-            resp,
-            Response::ConformingResp {
-                method: "isPrime".to_string(),
-                prime: false
-            }
-        );
-        assert_eq!(
-            resp.into_bytes(),
-            "{\"method\":\"isPrime\",\"prime\":false}\n"
-                .to_string()
-                .as_bytes()
-                .to_vec()
+            response,
+            b"{\"method\":\"isPrime\",\"prime\":true}\n".to_vec()
         );
     }
 
     #[test]
-    fn test_req_to_vec_not_prime_negative_success() {
-        let line = b"{\"number\":-3,\"method\":\"isPrime\"}";
-        let req = Request::from_bytes(line);
+    fn test_cost_of_is_prime_small_number() {
+        assert_eq!(cost_of_is_prime(&serde_json::json!({ "number": 11 })), 1);
+    }
 
+    #[test]
+    fn test_cost_of_is_prime_large_number() {
         assert_eq!(
-            req,
-            Request::ConformingReq {
-                method: "isPrime".to_string(),
-                number: -3f64
-            }
-        );
-        let resp = req.process();
-        assert_eq!(
-            resp,
-            Response::ConformingResp {
-                method: "isPrime".to_string(),
-                prime: false
-            }
-        );
-        assert_eq!(
-            resp.into_bytes(),
-            "{\"method\":\"isPrime\",\"prime\":false}\n"
-                .to_string()
-                .as_bytes()
-                .to_vec()
+            cost_of_is_prime(&serde_json::json!({ "number": u64::MAX - 58 })),
+            5
         );
     }
 
     #[test]
-    fn test_req_to_vec_float_success() {
-        let line = b"{\"method\":\"isPrime\",\"number\":4224223.1234}\n";
-        let req = Request::from_bytes(line);
+    fn test_cost_of_is_prime_missing_number_defaults_to_one() {
+        assert_eq!(cost_of_is_prime(&serde_json::json!({})), 1);
+    }
 
-        assert_eq!(
-            req,
-            Request::ConformingReq {
-                method: "isPrime".to_string(),
-                number: 4224223.1234f64
-            }
-        );
-        let resp = req.process();
-        assert_eq!(
-            resp,
-            Response::ConformingResp {
-                method: "isPrime".to_string(),
-                prime: false
-            }
-        );
-        assert_eq!(
-            resp.into_bytes(),
-            "{\"method\":\"isPrime\",\"prime\":false}\n"
-                .to_string()
-                .as_bytes()
-                .to_vec()
-        );
+    #[tokio::test]
+    async fn test_registered_unknown_method_is_malformed() {
+        let mut protocol = JsonMethodProtocol::new();
+        protocol.register("isPrime", is_prime_handler);
+
+        let err = protocol
+            .process_request(b"{\"method\":\"isPrim\",\"number\":123}")
+            .await
+            .unwrap_err();
+
+        assert_eq!(err, SolutionError::Request(b"malformed\n".to_vec()));
     }
 }
@@ -0,0 +1,569 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use traits::{Protocol, RequestDelimiter, SolutionError};
+
+/// Custom Error type used to treat Server specific errors
+#[derive(Debug, PartialEq)]
+pub enum ServerErrorKind {
+    BindFail,
+    ReadFail,
+    WriteFail,
+}
+
+#[async_trait]
+pub trait Server {
+    /// Method that starts the server
+    async fn run(&mut self, addr: &str) -> Result<(), ServerErrorKind>;
+}
+
+/// Drives a single connection to completion using a `Protocol` impl.
+///
+/// Reads one request per `protocol.get_delimiter()` (a `read_until` for
+/// `RequestDelimiter::UntilChar`, a `read_exact` into a sized buffer for
+/// `RequestDelimiter::NoOfBytes`), hands it to `process_request`, and
+/// writes back whatever bytes come out. `SolutionError::Request(bytes)`
+/// writes those bytes and then disconnects, mirroring a malformed
+/// response; `SolutionError::Read`/`Write` abort the connection outright.
+pub async fn serve_protocol<P: Protocol>(
+    stream: TcpStream,
+    mut protocol: P,
+) -> Result<(), ServerErrorKind> {
+    let mut stream = BufStream::new(stream);
+
+    loop {
+        let line = match protocol.get_delimiter() {
+            RequestDelimiter::UntilChar(delim) => {
+                let mut buf = vec![];
+                let read_len = stream
+                    .read_until(delim, &mut buf)
+                    .await
+                    .map_err(|_| ServerErrorKind::ReadFail)?;
+
+                if read_len == 0 {
+                    break;
+                }
+
+                buf
+            }
+            RequestDelimiter::NoOfBytes(n) => {
+                let mut buf = vec![0u8; n];
+
+                match stream.read_exact(&mut buf).await {
+                    Ok(_) => buf,
+                    Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                    Err(_) => return Err(ServerErrorKind::ReadFail),
+                }
+            }
+        };
+
+        let (response, should_continue) = match protocol.process_request(&line).await {
+            Ok(bytes) => (bytes, true),
+            Err(SolutionError::Request(bytes)) => (bytes, false),
+            Err(SolutionError::Read) => return Err(ServerErrorKind::ReadFail),
+            Err(SolutionError::Write) => return Err(ServerErrorKind::WriteFail),
+        };
+
+        if !response.is_empty() {
+            stream
+                .write_all(&response)
+                .await
+                .map_err(|_| ServerErrorKind::WriteFail)?;
+
+            stream
+                .flush()
+                .await
+                .map_err(|_| ServerErrorKind::WriteFail)?;
+        }
+
+        if !should_continue {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Bytes written back for any malformed JSON-RPC request.
+const MALFORMED_RESPONSE: &[u8] = b"malformed\n";
+
+/// Bytes written back when a request can't be granted enough flow-control
+/// credits to ever run (a zero-refill bucket with its burst spent).
+const RATE_LIMITED_RESPONSE: &[u8] = b"rate limited\n";
+
+/// A boxed async method handler, taking the request's fields (minus
+/// `method`) as a `serde_json::Value` and returning the fields to merge
+/// into the response object.
+type MethodHandler = Box<
+    dyn Fn(serde_json::Value) -> Pin<Box<dyn Future<Output = Result<serde_json::Value, SolutionError>> + Send>>
+        + Send
+        + Sync,
+>;
+
+/// A per-method cost hook, used to weigh flow-control credits before a
+/// handler runs.
+type CostFn = Box<dyn Fn(&serde_json::Value) -> u32 + Send + Sync>;
+
+/// Configuration for the per-connection request-credit token bucket.
+///
+/// Each connection starts with `burst` credits and refills at
+/// `credits_per_second`, capped at `burst`. A request whose cost exceeds
+/// the available balance is held (the read loop pauses) until enough
+/// credits accrue, rather than dispatching unbounded work.
+///
+/// `credits_per_second: 0` is a valid "fixed burst, no replenishment"
+/// policy: once the initial `burst` credits are spent, a request is
+/// rejected with a rate-limited response instead of waiting for a refill
+/// that will never come.
+#[derive(Debug, Clone, Copy)]
+pub struct FlowControl {
+    pub credits_per_second: u32,
+    pub burst: u32,
+}
+
+impl Default for FlowControl {
+    /// 100 credits/s with a burst of 100, enough headroom that a
+    /// well-behaved client issuing default-cost (1) requests never waits.
+    fn default() -> Self {
+        Self {
+            credits_per_second: 100,
+            burst: 100,
+        }
+    }
+}
+
+/// The token bucket backing a single connection's `FlowControl`.
+struct CreditBucket {
+    credits: f64,
+    credits_per_second: f64,
+    burst: f64,
+    last_refill: Instant,
+}
+
+impl CreditBucket {
+    fn new(flow_control: FlowControl) -> Self {
+        Self {
+            credits: flow_control.burst as f64,
+            credits_per_second: flow_control.credits_per_second as f64,
+            burst: flow_control.burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+
+        self.credits = (self.credits + elapsed * self.credits_per_second).min(self.burst);
+        self.last_refill = now;
+    }
+
+    /// Waits, if necessary, until `cost` credits are available, then
+    /// debits them. Returns `SolutionError::Request` instead of waiting
+    /// when the bucket has no way to ever accrue enough credits: a
+    /// `burst: 0` bucket (which can never hold any credits, regardless of
+    /// `credits_per_second`), or a zero-refill ("fixed burst") bucket
+    /// whose burst is already spent.
+    ///
+    /// A cost above `burst` is clamped to `burst`, since the bucket can
+    /// never hold more than that: without clamping, such a request would
+    /// wait forever for credits that can never accrue. Every cost is also
+    /// floored at 1, so a cost hook returning 0 still debits one credit.
+    async fn debit(&mut self, cost: u32) -> Result<(), SolutionError> {
+        if self.burst == 0.0 {
+            return Err(SolutionError::Request(RATE_LIMITED_RESPONSE.to_vec()));
+        }
+
+        let cost = (cost as f64).min(self.burst).max(1.0);
+
+        loop {
+            self.refill();
+
+            if self.credits >= cost {
+                self.credits -= cost;
+                return Ok(());
+            }
+
+            if self.credits_per_second == 0.0 {
+                return Err(SolutionError::Request(RATE_LIMITED_RESPONSE.to_vec()));
+            }
+
+            let deficit = cost - self.credits;
+            tokio::time::sleep(Duration::from_secs_f64(deficit / self.credits_per_second)).await;
+        }
+    }
+}
+
+/// A `Protocol` that dispatches newline-delimited JSON-RPC requests to
+/// handlers registered by method name.
+///
+/// Each request must be a well-formed JSON object with a `method` field
+/// naming a registered handler; the remaining fields are passed to that
+/// handler as `args`, and whatever object it returns is sent back with
+/// `method` echoed in. An unknown method, or a body that isn't a
+/// well-formed JSON object, is treated as a malformed request.
+///
+/// This turns a solution from one struct per problem into a set of
+/// methods registered on a single connection.
+///
+/// Each connection also carries its own `FlowControl` credit bucket
+/// (defaulted via `new`, or set via `with_flow_control`); a method
+/// registered with `register_cost` debits against it before its handler
+/// runs, pausing the connection's reads rather than the handler itself
+/// when credits run dry. Methods without a registered cost default to 1.
+pub struct JsonMethodProtocol {
+    handlers: HashMap<String, MethodHandler>,
+    costs: HashMap<String, CostFn>,
+    credits: CreditBucket,
+}
+
+impl Default for JsonMethodProtocol {
+    fn default() -> Self {
+        Self::with_flow_control(FlowControl::default())
+    }
+}
+
+impl JsonMethodProtocol {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a protocol whose credit bucket is configured by
+    /// `flow_control`, instead of the default rate.
+    pub fn with_flow_control(flow_control: FlowControl) -> Self {
+        Self {
+            handlers: HashMap::new(),
+            costs: HashMap::new(),
+            credits: CreditBucket::new(flow_control),
+        }
+    }
+
+    /// Registers an async handler for `name`.
+    pub fn register<F, Fut>(&mut self, name: &str, handler: F)
+    where
+        F: Fn(serde_json::Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<serde_json::Value, SolutionError>> + Send + 'static,
+    {
+        self.handlers
+            .insert(name.to_string(), Box::new(move |args| Box::pin(handler(args))));
+    }
+
+    /// Registers a flow-control cost hook for `name`, called with the
+    /// request's `args` to determine how many credits it debits before
+    /// its handler runs.
+    pub fn register_cost<C>(&mut self, name: &str, cost_of: C)
+    where
+        C: Fn(&serde_json::Value) -> u32 + Send + Sync + 'static,
+    {
+        self.costs.insert(name.to_string(), Box::new(cost_of));
+    }
+}
+
+#[async_trait]
+impl Protocol for JsonMethodProtocol {
+    async fn process_request(&mut self, line: &[u8]) -> Result<Vec<u8>, SolutionError> {
+        let malformed = || SolutionError::Request(MALFORMED_RESPONSE.to_vec());
+
+        let mut obj = match serde_json::from_slice::<serde_json::Value>(line) {
+            Ok(serde_json::Value::Object(obj)) => obj,
+            _ => return Err(malformed()),
+        };
+
+        let method = match obj.remove("method") {
+            Some(serde_json::Value::String(method)) => method,
+            _ => return Err(malformed()),
+        };
+
+        let handler = self.handlers.get(&method).ok_or_else(malformed)?;
+        let args = serde_json::Value::Object(obj);
+
+        let cost = self
+            .costs
+            .get(&method)
+            .map_or(1, |cost_of| cost_of(&args));
+        self.credits.debit(cost).await?;
+
+        let mut response = match handler(args).await? {
+            serde_json::Value::Object(response) => response,
+            _ => return Err(malformed()),
+        };
+        response.insert("method".to_string(), serde_json::Value::String(method));
+
+        let mut bytes =
+            serde_json::to_vec(&serde_json::Value::Object(response)).map_err(|_| SolutionError::Write)?;
+        bytes.push(b'\n');
+
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn echo_number(args: serde_json::Value) -> Result<serde_json::Value, SolutionError> {
+        Ok(serde_json::json!({ "number": args.get("number").cloned() }))
+    }
+
+    #[tokio::test]
+    async fn test_dispatches_to_registered_method() {
+        let mut protocol = JsonMethodProtocol::new();
+        protocol.register("echo", echo_number);
+
+        let response = protocol
+            .process_request(b"{\"method\":\"echo\",\"number\":42}")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response,
+            b"{\"method\":\"echo\",\"number\":42}\n".to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_method_is_malformed() {
+        let mut protocol = JsonMethodProtocol::new();
+        protocol.register("echo", echo_number);
+
+        let err = protocol
+            .process_request(b"{\"method\":\"unknown\",\"number\":42}")
+            .await
+            .unwrap_err();
+
+        assert_eq!(err, SolutionError::Request(MALFORMED_RESPONSE.to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_non_object_body_is_malformed() {
+        let mut protocol = JsonMethodProtocol::new();
+        protocol.register("echo", echo_number);
+
+        let err = protocol.process_request(b"[1,2,3]").await.unwrap_err();
+
+        assert_eq!(err, SolutionError::Request(MALFORMED_RESPONSE.to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_missing_method_field_is_malformed() {
+        let mut protocol = JsonMethodProtocol::new();
+        protocol.register("echo", echo_number);
+
+        let err = protocol
+            .process_request(b"{\"number\":42}")
+            .await
+            .unwrap_err();
+
+        assert_eq!(err, SolutionError::Request(MALFORMED_RESPONSE.to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_uncosted_method_defaults_to_one_credit() {
+        let flow_control = FlowControl {
+            credits_per_second: 0,
+            burst: 2,
+        };
+        let mut protocol = JsonMethodProtocol::with_flow_control(flow_control);
+        protocol.register("echo", echo_number);
+
+        // Two burst credits cover exactly two default-cost requests with no
+        // wait; the third finds the bucket empty with no refill coming.
+        protocol
+            .process_request(b"{\"method\":\"echo\",\"number\":1}")
+            .await
+            .unwrap();
+        protocol
+            .process_request(b"{\"method\":\"echo\",\"number\":2}")
+            .await
+            .unwrap();
+
+        let err = protocol
+            .process_request(b"{\"method\":\"echo\",\"number\":3}")
+            .await
+            .unwrap_err();
+        assert_eq!(err, SolutionError::Request(RATE_LIMITED_RESPONSE.to_vec()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_registered_cost_pauses_until_bucket_refills() {
+        let flow_control = FlowControl {
+            credits_per_second: 1,
+            burst: 2,
+        };
+        let mut protocol = JsonMethodProtocol::with_flow_control(flow_control);
+        protocol.register("echo", echo_number);
+        protocol.register_cost("echo", |_| 2);
+
+        let start = Instant::now();
+
+        // The first request spends the full 2-credit burst; the second
+        // must wait ~2s (2 credits at 1 credit/s) for the bucket to refill.
+        protocol
+            .process_request(b"{\"method\":\"echo\",\"number\":1}")
+            .await
+            .unwrap();
+        protocol
+            .process_request(b"{\"method\":\"echo\",\"number\":2}")
+            .await
+            .unwrap();
+
+        assert!(Instant::now().duration_since(start) >= Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cost_above_burst_is_clamped_instead_of_hanging() {
+        let flow_control = FlowControl {
+            credits_per_second: 1,
+            burst: 1,
+        };
+        let mut protocol = JsonMethodProtocol::with_flow_control(flow_control);
+        protocol.register("echo", echo_number);
+        protocol.register_cost("echo", |_| 1_000);
+
+        // Would wait forever for 1000 credits in a 1-credit bucket if the
+        // cost weren't clamped to `burst`.
+        protocol
+            .process_request(b"{\"method\":\"echo\",\"number\":1}")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_zero_refill_rejects_once_burst_is_spent() {
+        let flow_control = FlowControl {
+            credits_per_second: 0,
+            burst: 1,
+        };
+        let mut protocol = JsonMethodProtocol::with_flow_control(flow_control);
+        protocol.register("echo", echo_number);
+
+        // The single burst credit covers the first request with no wait.
+        protocol
+            .process_request(b"{\"method\":\"echo\",\"number\":1}")
+            .await
+            .unwrap();
+
+        // A zero refill rate can never earn back that credit, so the
+        // second request is rejected instead of waiting (or panicking on
+        // a division by zero) forever.
+        let err = protocol
+            .process_request(b"{\"method\":\"echo\",\"number\":2}")
+            .await
+            .unwrap_err();
+        assert_eq!(err, SolutionError::Request(RATE_LIMITED_RESPONSE.to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_zero_burst_rejects_immediately_instead_of_disabling_flow_control() {
+        let flow_control = FlowControl {
+            credits_per_second: 0,
+            burst: 0,
+        };
+        let mut protocol = JsonMethodProtocol::with_flow_control(flow_control);
+        protocol.register("echo", echo_number);
+
+        // A 0-credit bucket must not clamp every cost down to 0: that
+        // would make `credits >= cost` (`0 >= 0`) vacuously true and
+        // disable flow control entirely.
+        let err = protocol
+            .process_request(b"{\"method\":\"echo\",\"number\":1}")
+            .await
+            .unwrap_err();
+        assert_eq!(err, SolutionError::Request(RATE_LIMITED_RESPONSE.to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_zero_burst_rejects_immediately_even_with_positive_refill_rate() {
+        let flow_control = FlowControl {
+            credits_per_second: 5,
+            burst: 0,
+        };
+        let mut protocol = JsonMethodProtocol::with_flow_control(flow_control);
+        protocol.register("echo", echo_number);
+
+        // Credits are capped at `burst` (0) on every refill, so a positive
+        // `credits_per_second` can never lift them above 0: without a
+        // dedicated `burst == 0` check, this would hang forever waiting
+        // for a cost (floored at 1) that can never be met.
+        let err = protocol
+            .process_request(b"{\"method\":\"echo\",\"number\":1}")
+            .await
+            .unwrap_err();
+        assert_eq!(err, SolutionError::Request(RATE_LIMITED_RESPONSE.to_vec()));
+    }
+
+    /// A `Protocol` that reads a fixed number of bytes per request and
+    /// echoes them back verbatim, used to exercise the
+    /// `RequestDelimiter::NoOfBytes` branch of `serve_protocol`.
+    struct FixedBytesEcho {
+        len: usize,
+    }
+
+    #[async_trait]
+    impl Protocol for FixedBytesEcho {
+        fn get_delimiter(&self) -> RequestDelimiter {
+            RequestDelimiter::NoOfBytes(self.len)
+        }
+
+        async fn process_request(&mut self, line: &[u8]) -> Result<Vec<u8>, SolutionError> {
+            Ok(line.to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_protocol_drives_until_char_delimiter_over_tcp() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut protocol = JsonMethodProtocol::new();
+            protocol.register("echo", echo_number);
+            serve_protocol(socket, protocol).await
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"{\"method\":\"echo\",\"number\":7}\n")
+            .await
+            .unwrap();
+
+        let mut response = vec![0u8; b"{\"method\":\"echo\",\"number\":7}\n".len()];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, b"{\"method\":\"echo\",\"number\":7}\n".to_vec());
+
+        drop(client);
+        assert_eq!(server.await.unwrap(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_serve_protocol_drives_no_of_bytes_delimiter_over_tcp() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            serve_protocol(socket, FixedBytesEcho { len: 3 }).await
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"abc").await.unwrap();
+
+        let mut response = [0u8; 3];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"abc");
+
+        client.write_all(b"def").await.unwrap();
+        let mut response = [0u8; 3];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"def");
+
+        drop(client);
+        assert_eq!(server.await.unwrap(), Ok(()));
+    }
+}
@@ -1,6 +1,5 @@
 use async_trait::async_trait;
-use std::marker::{Send, Sync};
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use std::marker::Sync;
 
 /// Custom Error type used to treat Solution specific errors
 #[derive(Debug, PartialEq)]
@@ -30,5 +29,5 @@ pub trait Protocol
     }
 
     /// Custom method to process each received request/line
-    fn process_request(&mut self, line: &[u8]) -> Result<Vec<u8>, SolutionError>;
+    async fn process_request(&mut self, line: &[u8]) -> Result<Vec<u8>, SolutionError>;
 }